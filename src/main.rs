@@ -1,5 +1,8 @@
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
 
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
 enum Op {
@@ -7,102 +10,305 @@ enum Op {
     Div,
     Mul,
     Sub,
+    Eq,
+    NotEq,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+    And,
+    Or,
+    BitAnd,
+    BitOr,
+    BitXor,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+/// The result of evaluating an expression: an integer, a float (for
+/// fractional literals and any arithmetic involving one), or the boolean
+/// produced by a comparison or logical operator.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Value {
+    Int(i32),
+    Float(f64),
+    Bool(bool),
+}
+
+#[derive(Debug, PartialEq)]
 enum Token {
     EOF,
-    Number(i32),
+    Number(f64),
     Operation(Op),
     LeftParen,
     RightParen,
+    Identifier(String),
+    Assign,
+    Let,
+}
+
+/// A 1-indexed line/column location in the source.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+struct Position {
+    line: usize,
+    column: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// The source range a token or error covers, from `start` (inclusive) to
+/// `end` (exclusive).
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+struct Span {
+    start: Position,
+    end: Position,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, PartialEq)]
 enum Side {
     Node(Box<AST>),
-    Leaf(i32),
+    Leaf(f64),
+    Variable(String),
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, PartialEq)]
 struct AST {
     operation: Op,
     left: Side,
     right: Side,
 }
 
-#[derive(Debug, Eq, PartialEq)]
-struct BadInput {
-    unexpected: char,
+#[derive(Debug, PartialEq)]
+enum LexError {
+    BadInput { unexpected: char, span: Span },
+    MalformedNumber { literal: String, span: Span },
 }
 
-impl fmt::Display for BadInput {
+impl fmt::Display for LexError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "invalid syntax. saw unexpected character: {:?}",
-            self.unexpected
-        )
+        match self {
+            LexError::BadInput { unexpected, span } => write!(
+                f,
+                "{}: invalid syntax. saw unexpected character: {:?}",
+                span.start, unexpected
+            ),
+            LexError::MalformedNumber { literal, span } => {
+                write!(f, "{}: malformed numeric literal: {:?}", span.start, literal)
+            }
+        }
     }
 }
 
-impl Error for BadInput {}
+impl Error for LexError {}
 
-#[derive(Debug, Eq, PartialEq)]
-struct ParseError {}
+#[derive(Debug, PartialEq)]
+enum ParseError {
+    UnexpectedToken { span: Span },
+    MissingRightParen { span: Span },
+    UnexpectedEof { span: Span },
+}
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "parse error")
+        match self {
+            ParseError::UnexpectedToken { span } => {
+                write!(f, "{}: unexpected token", span.start)
+            }
+            ParseError::MissingRightParen { span } => {
+                write!(f, "{}: missing right parenthesis", span.start)
+            }
+            ParseError::UnexpectedEof { span } => {
+                write!(f, "{}: unexpected end of input", span.start)
+            }
+        }
     }
 }
 
 impl Error for ParseError {}
 
-fn lex(input: &str) -> Result<Vec<Token>, BadInput> {
-    let mut result: Vec<Token> = Vec::new();
+/// Tracks line/column position alongside a peekable char iterator so `lex`
+/// can attach a `Span` to every token it emits.
+struct Cursor<'a> {
+    chars: Peekable<Chars<'a>>,
+    position: Position,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(input: &'a str) -> Self {
+        Cursor {
+            chars: input.chars().peekable(),
+            position: Position { line: 1, column: 1 },
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn peek_second(&self) -> Option<char> {
+        let mut lookahead = self.chars.clone();
+        lookahead.next();
+        lookahead.next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let character = self.chars.next()?;
+
+        if character == '\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
+        }
 
-    for character in input.chars() {
+        Some(character)
+    }
+}
+
+fn lex(input: &str) -> Result<Vec<(Token, Span)>, LexError> {
+    let mut result: Vec<(Token, Span)> = Vec::new();
+    let mut cursor = Cursor::new(input);
+
+    while let Some(character) = cursor.peek() {
         use Op::*;
         use Token::*;
 
+        let start = cursor.position;
+
         match character {
-            ' ' => continue,
+            ' ' => {
+                cursor.next();
+            }
             ';' | '\n' => {
-                result.push(Token::EOF);
+                cursor.next();
+                result.push((
+                    Token::EOF,
+                    Span {
+                        start,
+                        end: cursor.position,
+                    },
+                ));
                 break;
             }
-            '+' => result.push(Operation(Add)),
-            '/' => result.push(Operation(Div)),
-            '*' => result.push(Operation(Mul)),
-            '-' => result.push(Operation(Sub)),
-            '(' => result.push(LeftParen),
-            ')' => result.push(RightParen),
-            '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => {
-                let num: i32 = (character as u8 - '0' as u8) as i32;
-
-                if result.len() == 0 {
-                    result.push(Number(num));
-                    continue;
-                }
+            '+' | '/' | '*' | '-' | '(' | ')' | '&' | '|' => {
+                cursor.next();
+
+                let token = match character {
+                    '+' => Operation(Add),
+                    '/' => Operation(Div),
+                    '*' => Operation(Mul),
+                    '-' => Operation(Sub),
+                    '(' => LeftParen,
+                    ')' => RightParen,
+                    '&' if cursor.peek() == Some('&') => {
+                        cursor.next();
+                        Operation(And)
+                    }
+                    '&' => Operation(BitAnd),
+                    '|' if cursor.peek() == Some('|') => {
+                        cursor.next();
+                        Operation(Or)
+                    }
+                    '|' => Operation(BitOr),
+                    _ => unreachable!(),
+                };
 
-                let last = result.pop().unwrap();
+                result.push((
+                    token,
+                    Span {
+                        start,
+                        end: cursor.position,
+                    },
+                ));
+            }
+            '^' => {
+                cursor.next();
+                result.push((
+                    Operation(BitXor),
+                    Span {
+                        start,
+                        end: cursor.position,
+                    },
+                ));
+            }
+            '=' | '!' | '<' | '>' => {
+                cursor.next();
 
-                match last {
-                    Number(i) => {
-                        result.push(Number((i * 10) + num));
+                let token = match (character, cursor.peek()) {
+                    ('=', Some('=')) => {
+                        cursor.next();
+                        Operation(Eq)
                     }
+                    ('=', _) => Token::Assign,
+                    ('!', Some('=')) => {
+                        cursor.next();
+                        Operation(NotEq)
+                    }
+                    ('<', Some('=')) => {
+                        cursor.next();
+                        Operation(Lte)
+                    }
+                    ('<', _) => Operation(Lt),
+                    ('>', Some('=')) => {
+                        cursor.next();
+                        Operation(Gte)
+                    }
+                    ('>', _) => Operation(Gt),
                     _ => {
-                        result.push(last);
-                        result.push(Number(num));
+                        return Err(LexError::BadInput {
+                            unexpected: character,
+                            span: Span {
+                                start,
+                                end: cursor.position,
+                            },
+                        })
                     }
-                }
-            }
+                };
 
+                result.push((
+                    token,
+                    Span {
+                        start,
+                        end: cursor.position,
+                    },
+                ));
+            }
+            '0'..='9' => {
+                let number = lex_number(&mut cursor)?;
+                result.push((
+                    Number(number),
+                    Span {
+                        start,
+                        end: cursor.position,
+                    },
+                ));
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let identifier = lex_identifier(&mut cursor);
+                let token = if identifier == "let" {
+                    Token::Let
+                } else {
+                    Token::Identifier(identifier)
+                };
+                result.push((
+                    token,
+                    Span {
+                        start,
+                        end: cursor.position,
+                    },
+                ));
+            }
             _ => {
-                return Err(BadInput {
+                cursor.next();
+                return Err(LexError::BadInput {
                     unexpected: character,
-                })
+                    span: Span {
+                        start,
+                        end: cursor.position,
+                    },
+                });
             }
         }
     }
@@ -110,95 +316,700 @@ fn lex(input: &str) -> Result<Vec<Token>, BadInput> {
     Ok(result)
 }
 
-fn parse(tokens: &[Token]) -> Result<i32, ParseError> {
-    use Token::*;
+/// Scans a full numeric literal under the cursor and parses it as `f64`.
+/// Handles hex integers (`0xFF`), decimal integers, and floats with an
+/// optional fractional part and an optional `e`/`E` exponent.
+fn lex_number(cursor: &mut Cursor) -> Result<f64, LexError> {
+    let start = cursor.position;
+    let mut literal = String::new();
 
-    let mut depth = 0;
-    let mut op_stack: Vec<(usize, Op, i32)> = Vec::new();
-    let mut value: Option<i32> = None;
+    if cursor.peek() == Some('0') && matches!(cursor.peek_second(), Some('x') | Some('X')) {
+        literal.push(cursor.next().unwrap());
+        literal.push(cursor.next().unwrap());
 
-    println!("{:?}", tokens);
+        let mut hex_digits = String::new();
 
-    for token in tokens {
-        println!("token: {:?}", token);
-        match token {
-            Operation(op) => {
-                if let Some(operand) = value {
-                    op_stack.push((depth, *op, operand));
-                    value = None;
-                } else {
-                    return Err(ParseError {});
-                }
+        while let Some(c) = cursor.peek() {
+            if c.is_ascii_hexdigit() {
+                hex_digits.push(c);
+                literal.push(c);
+                cursor.next();
+            } else {
+                break;
             }
-            Number(number) => {
-                if let Some((op_depth, op, operand)) = op_stack.pop() {
-                    op_stack.push((op_depth, op, operand));
-
-                    if op_depth == depth {
-                        return Ok(eval(&mut op_stack, *number));
-                    } else {
-                        value = Some(*number)
-                    }
-                } else {
-                    value = Some(*number)
-                }
+        }
+
+        let span = Span {
+            start,
+            end: cursor.position,
+        };
+
+        return if hex_digits.is_empty() {
+            Err(LexError::MalformedNumber { literal, span })
+        } else {
+            i64::from_str_radix(&hex_digits, 16)
+                .map(|n| n as f64)
+                .map_err(|_| LexError::MalformedNumber { literal, span })
+        };
+    }
+
+    while let Some(c) = cursor.peek() {
+        if c.is_ascii_digit() {
+            literal.push(c);
+            cursor.next();
+        } else {
+            break;
+        }
+    }
+
+    if cursor.peek() == Some('.') {
+        literal.push('.');
+        cursor.next();
+
+        while let Some(c) = cursor.peek() {
+            if c.is_ascii_digit() {
+                literal.push(c);
+                cursor.next();
+            } else {
+                break;
             }
-            LeftParen => depth += 1,
-            RightParen => depth -= 1,
-            EOF => {}
         }
-        println!(
-            "depth: {:?} value: {:?} op_stack: {:?}",
-            depth, value, op_stack
-        );
     }
 
-    Err(ParseError {})
+    if matches!(cursor.peek(), Some('e') | Some('E')) {
+        literal.push(cursor.next().unwrap());
+
+        if matches!(cursor.peek(), Some('+') | Some('-')) {
+            literal.push(cursor.next().unwrap());
+        }
+
+        while let Some(c) = cursor.peek() {
+            if c.is_ascii_digit() {
+                literal.push(c);
+                cursor.next();
+            } else {
+                break;
+            }
+        }
+    }
+
+    // A digit or `.` glued directly onto what looks like a finished literal
+    // (e.g. the second `.` in `1.2.3`) means the literal is malformed rather
+    // than two adjacent tokens, so fold it in and let the f64 parse fail.
+    while let Some(c) = cursor.peek() {
+        if c.is_ascii_digit() || c == '.' {
+            literal.push(c);
+            cursor.next();
+        } else {
+            break;
+        }
+    }
+
+    let span = Span {
+        start,
+        end: cursor.position,
+    };
+
+    literal
+        .parse::<f64>()
+        .map_err(|_| LexError::MalformedNumber { literal, span })
 }
 
-fn eval(op_stack: &mut Vec<(usize, Op, i32)>, initial_value: i32) -> i32 {
-    println!(
-        "eval called with op_stack={:?} initial_value={:?}",
-        op_stack, initial_value
-    );
+/// Scans an identifier (`[A-Za-z_][A-Za-z0-9_]*`) under the cursor. The
+/// leading character has already been confirmed to match by the caller.
+fn lex_identifier(cursor: &mut Cursor) -> String {
+    let mut identifier = String::new();
 
-    if let Some((_op_depth, op, operand)) = op_stack.pop() {
-        eval(op_stack, eval_op(op, operand, initial_value))
-    } else {
-        initial_value
+    while let Some(c) = cursor.peek() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            identifier.push(c);
+            cursor.next();
+        } else {
+            break;
+        }
     }
+
+    identifier
 }
 
-fn eval_op(op: Op, left: i32, right: i32) -> i32 {
-    println!("eval_op({:?}, {:?}, {:?})", op, left, right);
+/// Binding powers for infix operators, as `(left_bp, right_bp)` pairs. A
+/// higher right binding power than left makes `parse_expr` recurse on the
+/// right-hand side with a higher threshold than the operator's own left
+/// binding power, so a following operator of the same precedence stops the
+/// recursion; that is what makes same-precedence operators fold
+/// left-associatively. Precedence
+/// tiers from loosest to tightest binding: bitwise/logical or, bitwise/
+/// logical and, comparison, additive, multiplicative.
+fn binding_power(op: Op) -> (u8, u8) {
     use Op::*;
 
     match op {
-        Add => left + right,
-        Sub => left - right,
-        Mul => left * right,
-        Div => left / right,
-    }
-}
-
-fn unwind_with_ast(op_stack: &mut Vec<(usize, Op, i32)>, ast: AST) -> Result<AST, ParseError> {
-    println!(
-        "unwind_with_ast called with op_stack={:?} ast={:?}",
-        op_stack, ast
-    );
-    use Side::*;
-    if let Some((_op_depth, op, operand)) = op_stack.pop() {
-        unwind_with_ast(
-            op_stack,
-            AST {
-                operation: op,
-                left: Leaf(operand),
-                right: Node(Box::new(ast)),
-            },
-        )
+        BitOr | BitXor | Or => (1, 2),
+        BitAnd | And => (3, 4),
+        Eq | NotEq | Lt | Lte | Gt | Gte => (5, 6),
+        Add | Sub => (7, 8),
+        Mul | Div => (9, 10),
+    }
+}
+
+/// Returns the span to blame for an error at end-of-input: the end of the
+/// last token if there is one, otherwise the start of the file.
+fn eof_span(tokens: &[(Token, Span)]) -> Span {
+    match tokens.last() {
+        Some((_, span)) => Span {
+            start: span.end,
+            end: span.end,
+        },
+        None => {
+            let origin = Position { line: 1, column: 1 };
+            Span {
+                start: origin,
+                end: origin,
+            }
+        }
+    }
+}
+
+/// A single top-level statement: either a `let` binding or a bare
+/// expression whose value becomes the result of evaluating the statement.
+#[derive(Debug, PartialEq)]
+enum Statement {
+    Assignment { name: String, value: Side },
+    Expression(Side),
+}
+
+/// Parses one statement from `tokens`, using precedence climbing (a.k.a.
+/// Pratt parsing) for expressions so that operator precedence and
+/// left-associativity fall out of the recursion instead of being tracked
+/// by hand.
+fn parse_statement(tokens: &[(Token, Span)]) -> Result<Statement, ParseError> {
+    if let Some((Token::Let, _)) = tokens.first() {
+        let name = match tokens.get(1) {
+            Some((Token::Identifier(name), _)) => name.clone(),
+            Some((_, span)) => return Err(ParseError::UnexpectedToken { span: *span }),
+            None => {
+                return Err(ParseError::UnexpectedEof {
+                    span: eof_span(tokens),
+                })
+            }
+        };
+
+        match tokens.get(2) {
+            Some((Token::Assign, _)) => {}
+            Some((_, span)) => return Err(ParseError::UnexpectedToken { span: *span }),
+            None => {
+                return Err(ParseError::UnexpectedEof {
+                    span: eof_span(tokens),
+                })
+            }
+        }
+
+        let mut cursor = 3;
+        let value = parse_expr(tokens, &mut cursor, 0)?;
+        expect_end(tokens, cursor)?;
+
+        return Ok(Statement::Assignment { name, value });
+    }
+
+    Ok(Statement::Expression(parse_expression(tokens)?))
+}
+
+/// Parses a single expression, requiring the whole slice (up to an
+/// optional trailing EOF token) to be consumed.
+fn parse_expression(tokens: &[(Token, Span)]) -> Result<Side, ParseError> {
+    let mut cursor = 0;
+    let side = parse_expr(tokens, &mut cursor, 0)?;
+    expect_end(tokens, cursor)?;
+    Ok(side)
+}
+
+fn expect_end(tokens: &[(Token, Span)], cursor: usize) -> Result<(), ParseError> {
+    match tokens.get(cursor) {
+        None | Some((Token::EOF, _)) => Ok(()),
+        Some((_, span)) => Err(ParseError::UnexpectedToken { span: *span }),
+    }
+}
+
+fn parse_expr(
+    tokens: &[(Token, Span)],
+    cursor: &mut usize,
+    min_bp: u8,
+) -> Result<Side, ParseError> {
+    let mut left = parse_nud(tokens, cursor)?;
+
+    loop {
+        let op = match tokens.get(*cursor) {
+            Some((Token::Operation(op), _)) => *op,
+            _ => break,
+        };
+
+        let (left_bp, right_bp) = binding_power(op);
+
+        if left_bp < min_bp {
+            break;
+        }
+
+        *cursor += 1;
+
+        let right = parse_expr(tokens, cursor, right_bp)?;
+
+        left = Side::Node(Box::new(AST {
+            operation: op,
+            left,
+            right,
+        }));
+    }
+
+    Ok(left)
+}
+
+fn parse_nud(tokens: &[(Token, Span)], cursor: &mut usize) -> Result<Side, ParseError> {
+    match tokens.get(*cursor) {
+        Some((Token::Number(n), _)) => {
+            *cursor += 1;
+            Ok(Side::Leaf(*n))
+        }
+        Some((Token::Identifier(name), _)) => {
+            *cursor += 1;
+            Ok(Side::Variable(name.clone()))
+        }
+        Some((Token::LeftParen, _)) => {
+            *cursor += 1;
+            let inner = parse_expr(tokens, cursor, 0)?;
+
+            match tokens.get(*cursor) {
+                Some((Token::RightParen, _)) => {
+                    *cursor += 1;
+                    Ok(inner)
+                }
+                Some((_, span)) => Err(ParseError::MissingRightParen { span: *span }),
+                None => Err(ParseError::MissingRightParen {
+                    span: eof_span(tokens),
+                }),
+            }
+        }
+        Some((_, span)) => Err(ParseError::UnexpectedToken { span: *span }),
+        None => Err(ParseError::UnexpectedEof {
+            span: eof_span(tokens),
+        }),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum EvalError {
+    TypeMismatch {
+        operation: Op,
+        left: Value,
+        right: Value,
+    },
+    UndefinedVariable(String),
+    DivisionByZero,
+    Overflow,
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::TypeMismatch {
+                operation,
+                left,
+                right,
+            } => write!(
+                f,
+                "type error: {:?} does not apply to {:?} and {:?}",
+                operation, left, right
+            ),
+            EvalError::UndefinedVariable(name) => write!(f, "undefined variable: {}", name),
+            EvalError::DivisionByZero => write!(f, "division by zero"),
+            EvalError::Overflow => write!(f, "integer overflow"),
+        }
+    }
+}
+
+impl Error for EvalError {}
+
+/// Variable bindings created by `let` statements, carried across
+/// successive `eval_with_env` calls so a REPL can keep state between lines.
+#[derive(Debug, Default)]
+struct Environment {
+    variables: HashMap<String, Value>,
+}
+
+impl Environment {
+    fn new() -> Self {
+        Environment::default()
+    }
+
+    fn get(&self, name: &str) -> Result<Value, EvalError> {
+        self.variables
+            .get(name)
+            .copied()
+            .ok_or_else(|| EvalError::UndefinedVariable(name.to_string()))
+    }
+
+    fn set(&mut self, name: String, value: Value) {
+        self.variables.insert(name, value);
+    }
+}
+
+fn eval_ast(ast: &AST, env: &Environment) -> Result<Value, EvalError> {
+    if matches!(ast.operation, Op::And | Op::Or) {
+        return eval_short_circuit(ast, env);
+    }
+
+    eval_op(
+        ast.operation,
+        eval_side(&ast.left, env)?,
+        eval_side(&ast.right, env)?,
+    )
+}
+
+/// `&&`/`||` short-circuit: the right side is only evaluated when the left
+/// side's truthiness doesn't already decide the result, so a guard like
+/// `y != 0 && 10 / y > 2` never evaluates the division (and its potential
+/// `DivisionByZero`) once the left side is false.
+fn eval_short_circuit(ast: &AST, env: &Environment) -> Result<Value, EvalError> {
+    let left = eval_side(&ast.left, env)?;
+
+    let left_bool = match left {
+        Value::Bool(b) => b,
+        _ => {
+            // Only eval the right side to enrich the error message; a
+            // failure there (e.g. DivisionByZero) must not mask the real
+            // type-mismatch error, so fall back to `left` instead of `?`.
+            let right = eval_side(&ast.right, env).unwrap_or(left);
+            return Err(EvalError::TypeMismatch {
+                operation: ast.operation,
+                left,
+                right,
+            });
+        }
+    };
+
+    let short_circuits_on = matches!(ast.operation, Op::Or);
+
+    if left_bool == short_circuits_on {
+        return Ok(Value::Bool(left_bool));
+    }
+
+    match eval_side(&ast.right, env)? {
+        Value::Bool(right) => Ok(Value::Bool(right)),
+        right => Err(EvalError::TypeMismatch {
+            operation: ast.operation,
+            left,
+            right,
+        }),
+    }
+}
+
+fn eval_side(side: &Side, env: &Environment) -> Result<Value, EvalError> {
+    match side {
+        Side::Leaf(n) => Ok(literal_value(*n)),
+        Side::Variable(name) => env.get(name),
+        Side::Node(ast) => eval_ast(ast, env),
+    }
+}
+
+/// A literal with no fractional part (and within `i32` range) evaluates to
+/// an `Int`, matching every existing literal in this calculator; anything
+/// else (e.g. `3.14`) evaluates to a `Float` rather than being truncated.
+fn literal_value(n: f64) -> Value {
+    if n.fract() == 0.0 && n >= i32::MIN as f64 && n <= i32::MAX as f64 {
+        Value::Int(n as i32)
     } else {
-        Ok(ast)
+        Value::Float(n)
+    }
+}
+
+fn is_numeric(value: Value) -> bool {
+    !matches!(value, Value::Bool(_))
+}
+
+fn as_f64(value: Value) -> f64 {
+    match value {
+        Value::Int(n) => n as f64,
+        Value::Float(n) => n,
+        Value::Bool(_) => unreachable!("as_f64 called on a non-numeric value"),
+    }
+}
+
+fn eval_op(op: Op, left: Value, right: Value) -> Result<Value, EvalError> {
+    use Op::*;
+    use Value::*;
+
+    match (op, left, right) {
+        (Add, Int(l), Int(r)) => l.checked_add(r).map(Int).ok_or(EvalError::Overflow),
+        (Sub, Int(l), Int(r)) => l.checked_sub(r).map(Int).ok_or(EvalError::Overflow),
+        (Mul, Int(l), Int(r)) => l.checked_mul(r).map(Int).ok_or(EvalError::Overflow),
+        (Div, Int(_), Int(0)) => Err(EvalError::DivisionByZero),
+        (Div, Int(l), Int(r)) => l.checked_div(r).map(Int).ok_or(EvalError::Overflow),
+        (Add | Sub | Mul | Div, l, r) if is_numeric(l) && is_numeric(r) => {
+            let (l, r) = (as_f64(l), as_f64(r));
+
+            if op == Div && r == 0.0 {
+                return Err(EvalError::DivisionByZero);
+            }
+
+            Ok(Float(match op {
+                Add => l + r,
+                Sub => l - r,
+                Mul => l * r,
+                Div => l / r,
+                _ => unreachable!(),
+            }))
+        }
+        (Eq, Bool(l), Bool(r)) => Ok(Bool(l == r)),
+        (NotEq, Bool(l), Bool(r)) => Ok(Bool(l != r)),
+        (Eq | NotEq | Lt | Lte | Gt | Gte, l, r) if is_numeric(l) && is_numeric(r) => {
+            let (l, r) = (as_f64(l), as_f64(r));
+            Ok(Bool(match op {
+                Eq => l == r,
+                NotEq => l != r,
+                Lt => l < r,
+                Lte => l <= r,
+                Gt => l > r,
+                Gte => l >= r,
+                _ => unreachable!(),
+            }))
+        }
+        // And/Or are handled by eval_short_circuit before eval_ast ever
+        // calls eval_op, so they fall through to the TypeMismatch arm below
+        // if eval_op is ever called with them directly.
+        (BitAnd, Int(l), Int(r)) => Ok(Int(l & r)),
+        (BitOr, Int(l), Int(r)) => Ok(Int(l | r)),
+        (BitXor, Int(l), Int(r)) => Ok(Int(l ^ r)),
+        (operation, left, right) => Err(EvalError::TypeMismatch {
+            operation,
+            left,
+            right,
+        }),
+    }
+}
+
+fn eval_statement(statement: &Statement, env: &mut Environment) -> Result<Value, EvalError> {
+    match statement {
+        Statement::Assignment { name, value } => {
+            let evaluated = eval_side(value, env)?;
+            env.set(name.clone(), evaluated);
+            Ok(evaluated)
+        }
+        Statement::Expression(side) => eval_side(side, env),
+    }
+}
+
+/// Any failure from lexing, parsing, or evaluating a line of input.
+#[derive(Debug, PartialEq)]
+enum CalcError {
+    Lex(LexError),
+    Parse(ParseError),
+    Eval(EvalError),
+}
+
+impl fmt::Display for CalcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CalcError::Lex(e) => e.fmt(f),
+            CalcError::Parse(e) => e.fmt(f),
+            CalcError::Eval(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for CalcError {}
+
+impl From<LexError> for CalcError {
+    fn from(error: LexError) -> Self {
+        CalcError::Lex(error)
+    }
+}
+
+impl From<ParseError> for CalcError {
+    fn from(error: ParseError) -> Self {
+        CalcError::Parse(error)
+    }
+}
+
+impl From<EvalError> for CalcError {
+    fn from(error: EvalError) -> Self {
+        CalcError::Eval(error)
+    }
+}
+
+/// Evaluates `;`-separated statements against a persistent `Environment`,
+/// returning the value of the last one (e.g. `let x = 5 + 6; x * 2`), so a
+/// REPL can feed it one line at a time while keeping variables bound.
+fn eval_with_env(input: &str, env: &mut Environment) -> Result<Value, CalcError> {
+    let mut result = Value::Int(0);
+
+    for statement in input.split(';') {
+        let statement = statement.trim();
+
+        if statement.is_empty() {
+            continue;
+        }
+
+        let tokens = lex(statement)?;
+        let parsed = parse_statement(&tokens)?;
+        result = eval_statement(&parsed, env)?;
+    }
+
+    Ok(result)
+}
+
+/// A single bytecode operation. `PushConst` indexes into its `Chunk`'s
+/// constant pool; the arithmetic instructions pop their two operands off
+/// the VM's operand stack and push the result.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Instruction {
+    PushConst(usize),
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// A compiled program: a flat instruction stream plus the constant pool
+/// `PushConst` indexes into.
+#[derive(Debug, Default, PartialEq)]
+struct Chunk {
+    instructions: Vec<Instruction>,
+    constants: Vec<i32>,
+}
+
+impl Chunk {
+    fn new() -> Self {
+        Chunk::default()
+    }
+
+    fn push_const(&mut self, value: i32) -> usize {
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    /// Prints each instruction with its offset and, for `PushConst`, the
+    /// constant it resolves to - a debugging aid in place of the ad-hoc
+    /// `println!` tracing `parse`/`eval` used to scatter around.
+    fn disassemble(&self) {
+        for (offset, instruction) in self.instructions.iter().enumerate() {
+            match instruction {
+                Instruction::PushConst(index) => {
+                    println!("{:04} PushConst {} ({})", offset, index, self.constants[*index]);
+                }
+                other => println!("{:04} {:?}", offset, other),
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum CompileError {
+    UnsupportedOperator(Op),
+    UnsupportedVariable(String),
+    UnsupportedFloatLiteral(f64),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::UnsupportedOperator(op) => {
+                write!(f, "operator not supported by the compiler: {:?}", op)
+            }
+            CompileError::UnsupportedVariable(name) => {
+                write!(f, "variable not supported by the compiler: {}", name)
+            }
+            CompileError::UnsupportedFloatLiteral(n) => {
+                write!(f, "float literal not supported by the compiler: {}", n)
+            }
+        }
+    }
+}
+
+impl Error for CompileError {}
+
+/// Compiles `side` into a `Chunk`, walking the `AST` post-order so operands
+/// are already on the VM's stack by the time their operator instruction
+/// runs.
+fn compile(side: &Side) -> Result<Chunk, CompileError> {
+    let mut chunk = Chunk::new();
+    compile_into(side, &mut chunk)?;
+    Ok(chunk)
+}
+
+fn compile_into(side: &Side, chunk: &mut Chunk) -> Result<(), CompileError> {
+    match side {
+        Side::Leaf(n) => {
+            if n.fract() != 0.0 || *n < i32::MIN as f64 || *n > i32::MAX as f64 {
+                return Err(CompileError::UnsupportedFloatLiteral(*n));
+            }
+
+            let index = chunk.push_const(*n as i32);
+            chunk.instructions.push(Instruction::PushConst(index));
+            Ok(())
+        }
+        Side::Variable(name) => Err(CompileError::UnsupportedVariable(name.clone())),
+        Side::Node(ast) => {
+            compile_into(&ast.left, chunk)?;
+            compile_into(&ast.right, chunk)?;
+
+            let instruction = match ast.operation {
+                Op::Add => Instruction::Add,
+                Op::Sub => Instruction::Sub,
+                Op::Mul => Instruction::Mul,
+                Op::Div => Instruction::Div,
+                operation => return Err(CompileError::UnsupportedOperator(operation)),
+            };
+
+            chunk.instructions.push(instruction);
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum VmError {
+    StackUnderflow,
+    DivisionByZero,
+    Overflow,
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VmError::StackUnderflow => write!(f, "operand stack underflow"),
+            VmError::DivisionByZero => write!(f, "division by zero"),
+            VmError::Overflow => write!(f, "integer overflow"),
+        }
+    }
+}
+
+impl Error for VmError {}
+
+/// Runs `chunk` on a fresh operand stack and returns the final value.
+fn run(chunk: &Chunk) -> Result<i32, VmError> {
+    let mut stack: Vec<i32> = Vec::new();
+
+    for instruction in &chunk.instructions {
+        match instruction {
+            Instruction::PushConst(index) => stack.push(chunk.constants[*index]),
+            Instruction::Add | Instruction::Sub | Instruction::Mul | Instruction::Div => {
+                let right = stack.pop().ok_or(VmError::StackUnderflow)?;
+                let left = stack.pop().ok_or(VmError::StackUnderflow)?;
+
+                let result = match instruction {
+                    Instruction::Add => left.checked_add(right).ok_or(VmError::Overflow)?,
+                    Instruction::Sub => left.checked_sub(right).ok_or(VmError::Overflow)?,
+                    Instruction::Mul => left.checked_mul(right).ok_or(VmError::Overflow)?,
+                    Instruction::Div if right == 0 => return Err(VmError::DivisionByZero),
+                    Instruction::Div => left.checked_div(right).ok_or(VmError::Overflow)?,
+                    Instruction::PushConst(_) => unreachable!(),
+                };
+
+                stack.push(result);
+            }
+        }
     }
+
+    stack.pop().ok_or(VmError::StackUnderflow)
 }
 
 fn main() {}
@@ -207,53 +1018,320 @@ fn main() {}
 mod tests {
     use super::{Op::*, Side::*, Token::*, *};
 
+    fn tokens_only(pairs: Vec<(Token, Span)>) -> Vec<Token> {
+        pairs.into_iter().map(|(token, _)| token).collect()
+    }
+
     #[test]
     fn lexing() {
         assert!(lex("420 + 69").is_ok());
         assert!(lex("mmmm, brains").is_err());
 
-        assert_eq!(lex(""), Ok(vec![]));
-        assert_eq!(lex("2+2"), Ok(vec![Number(2), Operation(Add), Number(2)]));
+        assert_eq!(lex("").map(tokens_only), Ok(vec![]));
+        assert_eq!(
+            lex("2+2").map(tokens_only),
+            Ok(vec![Number(2.0), Operation(Add), Number(2.0)])
+        );
         assert_eq!(
-            lex("(2+(3+4)*5)/6 - 1"),
+            lex("(2+(3+4)*5)/6 - 1").map(tokens_only),
             Ok(vec![
                 LeftParen,
-                Number(2),
+                Number(2.0),
                 Operation(Add),
                 LeftParen,
-                Number(3),
+                Number(3.0),
                 Operation(Add),
-                Number(4),
+                Number(4.0),
                 RightParen,
                 Operation(Mul),
-                Number(5),
+                Number(5.0),
                 RightParen,
                 Operation(Div),
-                Number(6),
+                Number(6.0),
                 Operation(Sub),
-                Number(1)
+                Number(1.0)
             ])
         );
     }
 
+    #[test]
+    fn lexing_floats_and_bases() {
+        assert_eq!(lex("3.14").map(tokens_only), Ok(vec![Number(3.14)]));
+        assert_eq!(lex("1e9").map(tokens_only), Ok(vec![Number(1e9)]));
+        assert_eq!(lex("1.5e-2").map(tokens_only), Ok(vec![Number(1.5e-2)]));
+        assert_eq!(lex("0xFF").map(tokens_only), Ok(vec![Number(255.0)]));
+
+        assert!(matches!(
+            lex("1.2.3"),
+            Err(LexError::MalformedNumber { literal, .. }) if literal == "1.2.3"
+        ));
+        assert!(matches!(
+            lex("1e"),
+            Err(LexError::MalformedNumber { literal, .. }) if literal == "1e"
+        ));
+    }
+
+    #[test]
+    fn lexing_tracks_positions() {
+        let tokens = lex("12\n+ 3").unwrap();
+
+        assert_eq!(
+            tokens[0].1,
+            Span {
+                start: Position { line: 1, column: 1 },
+                end: Position { line: 1, column: 3 },
+            }
+        );
+        assert_eq!(tokens[1].1.start, Position { line: 1, column: 3 });
+
+        let err = lex("1 @").unwrap_err();
+        assert_eq!(
+            err,
+            LexError::BadInput {
+                unexpected: '@',
+                span: Span {
+                    start: Position { line: 1, column: 3 },
+                    end: Position { line: 1, column: 4 },
+                },
+            }
+        );
+    }
+
     #[test]
     fn parsing() {
+        let tokens = lex("2 + 2").unwrap();
+
         assert_eq!(
-            parse(&mut vec![Number(2), Operation(Add), Number(2)]),
-            Ok(4)
+            parse_expression(&tokens),
+            Ok(Node(Box::new(AST {
+                operation: Add,
+                left: Leaf(2.0),
+                right: Leaf(2.0),
+            })))
         );
 
+        let tokens = lex("5 + (4 - 3)").unwrap();
+
         assert_eq!(
-            parse(&mut vec![
-                Number(5),
-                Operation(Add),
-                LeftParen,
-                Number(4),
-                Operation(Sub),
-                Number(3),
-                RightParen
-            ]),
-            Ok(6)
+            parse_expression(&tokens),
+            Ok(Node(Box::new(AST {
+                operation: Add,
+                left: Leaf(5.0),
+                right: Node(Box::new(AST {
+                    operation: Sub,
+                    left: Leaf(4.0),
+                    right: Leaf(3.0),
+                })),
+            })))
+        );
+    }
+
+    #[test]
+    fn parse_errors_report_position() {
+        let tokens = lex("(1 + 2").unwrap();
+        assert!(matches!(
+            parse_expression(&tokens),
+            Err(ParseError::MissingRightParen { .. })
+        ));
+
+        let tokens = lex("1 +").unwrap();
+        assert!(matches!(
+            parse_expression(&tokens),
+            Err(ParseError::UnexpectedEof { .. })
+        ));
+    }
+
+    #[test]
+    fn precedence() {
+        let tokens = lex("2 + 3 * 4").unwrap();
+        let side = parse_expression(&tokens).unwrap();
+
+        assert_eq!(eval_side(&side, &Environment::new()), Ok(Value::Int(14)));
+    }
+
+    #[test]
+    fn evaluating() {
+        let tokens = lex("(2+(3+4)*5)/6 - 1").unwrap();
+        let side = parse_expression(&tokens).unwrap();
+
+        assert_eq!(
+            eval_side(&side, &Environment::new()),
+            Ok(Value::Int(37 / 6 - 1))
+        );
+    }
+
+    #[test]
+    fn fractional_literals_evaluate_as_floats_end_to_end() {
+        let tokens = lex("3.14 * 2").unwrap();
+        let side = parse_expression(&tokens).unwrap();
+
+        assert_eq!(
+            eval_side(&side, &Environment::new()),
+            Ok(Value::Float(6.28))
+        );
+        assert_eq!(
+            eval_with_env("3.14 * 2", &mut Environment::new()),
+            Ok(Value::Float(6.28))
+        );
+    }
+
+    #[test]
+    fn short_circuiting_and_or_skip_the_unevaluated_side() {
+        let mut env = Environment::new();
+        env.set("y".to_string(), Value::Int(0));
+
+        assert_eq!(
+            eval_with_env("y != 0 && 10 / y > 2", &mut env),
+            Ok(Value::Bool(false))
+        );
+        assert_eq!(
+            eval_with_env("y == 0 || 10 / y > 2", &mut env),
+            Ok(Value::Bool(true))
+        );
+    }
+
+    #[test]
+    fn a_non_bool_left_operand_reports_type_mismatch_even_if_the_right_side_errors() {
+        assert!(matches!(
+            eval_with_env("1 && 10 / 0", &mut Environment::new()),
+            Err(CalcError::Eval(EvalError::TypeMismatch { operation: And, .. }))
+        ));
+    }
+
+    #[test]
+    fn comparisons_and_logic_produce_bools() {
+        let tokens = lex("1 < 2 && 3 > 2").unwrap();
+        let side = parse_expression(&tokens).unwrap();
+
+        assert_eq!(eval_side(&side, &Environment::new()), Ok(Value::Bool(true)));
+    }
+
+    #[test]
+    fn bitwise_or_binds_loosest() {
+        // `+` binds tighter than `==`, which binds tighter than `|`, so
+        // this parses as `1 | ((2 + 3) == 5)`, not `(1 | 2) + (3 == 5)`.
+        let tokens = lex("1 | 2 + 3 == 5").unwrap();
+        let side = parse_expression(&tokens).unwrap();
+
+        let ast = match side {
+            Node(ast) => ast,
+            Leaf(_) | Variable(_) => panic!("expected a node"),
+        };
+
+        assert_eq!(ast.operation, BitOr);
+        assert_eq!(ast.left, Leaf(1.0));
+        assert!(matches!(ast.right, Node(ref inner) if inner.operation == Eq));
+    }
+
+    #[test]
+    fn type_errors_on_mismatched_operands() {
+        let tokens = lex("(1 < 2) + 3").unwrap();
+        let side = parse_expression(&tokens).unwrap();
+
+        assert!(matches!(
+            eval_side(&side, &Environment::new()),
+            Err(EvalError::TypeMismatch { operation: Add, .. })
+        ));
+    }
+
+    #[test]
+    fn variables_persist_across_statements() {
+        let mut env = Environment::new();
+
+        assert_eq!(
+            eval_with_env("let x = 5 + 6", &mut env),
+            Ok(Value::Int(11))
+        );
+        assert_eq!(eval_with_env("x * 2", &mut env), Ok(Value::Int(22)));
+        assert_eq!(
+            eval_with_env("let x = 5 + 6; x * 2", &mut Environment::new()),
+            Ok(Value::Int(22))
+        );
+    }
+
+    #[test]
+    fn division_by_zero_is_an_error_not_a_panic() {
+        assert_eq!(
+            eval_with_env("1 / 0", &mut Environment::new()),
+            Err(CalcError::Eval(EvalError::DivisionByZero))
+        );
+    }
+
+    #[test]
+    fn overflowing_multiplication_is_an_error_not_a_panic() {
+        assert_eq!(
+            eval_with_env("2000000 * 2000000", &mut Environment::new()),
+            Err(CalcError::Eval(EvalError::Overflow))
+        );
+    }
+
+    #[test]
+    fn referencing_an_undefined_variable_is_an_error() {
+        assert_eq!(
+            eval_with_env("y + 1", &mut Environment::new()),
+            Err(CalcError::Eval(EvalError::UndefinedVariable(
+                "y".to_string()
+            )))
+        );
+    }
+
+    #[test]
+    fn compiling_and_running_matches_tree_walking_eval() {
+        let tokens = lex("2 + 3 * 4").unwrap();
+        let side = parse_expression(&tokens).unwrap();
+
+        let chunk = compile(&side).unwrap();
+
+        assert_eq!(
+            chunk,
+            Chunk {
+                instructions: vec![
+                    Instruction::PushConst(0),
+                    Instruction::PushConst(1),
+                    Instruction::PushConst(2),
+                    Instruction::Mul,
+                    Instruction::Add,
+                ],
+                constants: vec![2, 3, 4],
+            }
+        );
+        assert_eq!(run(&chunk), Ok(14));
+
+        chunk.disassemble();
+    }
+
+    #[test]
+    fn vm_reports_division_by_zero_and_overflow() {
+        let tokens = lex("1 / 0").unwrap();
+        let chunk = compile(&parse_expression(&tokens).unwrap()).unwrap();
+        assert_eq!(run(&chunk), Err(VmError::DivisionByZero));
+
+        let tokens = lex("2000000 * 2000000").unwrap();
+        let chunk = compile(&parse_expression(&tokens).unwrap()).unwrap();
+        assert_eq!(run(&chunk), Err(VmError::Overflow));
+    }
+
+    #[test]
+    fn compiling_an_unsupported_construct_is_an_error() {
+        let tokens = lex("1 == 2").unwrap();
+        let side = parse_expression(&tokens).unwrap();
+
+        assert_eq!(compile(&side), Err(CompileError::UnsupportedOperator(Eq)));
+
+        let tokens = lex("x + 1").unwrap();
+        let side = parse_expression(&tokens).unwrap();
+
+        assert_eq!(
+            compile(&side),
+            Err(CompileError::UnsupportedVariable("x".to_string()))
+        );
+
+        let tokens = lex("3.14 + 1").unwrap();
+        let side = parse_expression(&tokens).unwrap();
+
+        assert_eq!(
+            compile(&side),
+            Err(CompileError::UnsupportedFloatLiteral(3.14))
         );
     }
 }